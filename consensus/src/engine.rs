@@ -14,10 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
 use std::fmt;
 
 use bytes::Bytes;
 use codechain_types::{Address, H256};
+use codechain_types::transaction::SignedTransaction;
 use keys::Signature;
 use rlp::{Encodable, Decodable, DecoderError, RlpStream, UntrustedRlp};
 
@@ -36,6 +38,17 @@ pub enum Seal {
     None,
 }
 
+/// The engine's readiness to generate a seal internally, without external (e.g. PoW) input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SealingState {
+    /// The engine is ready to seal right now; it is this node's turn.
+    Ready,
+    /// The engine seals internally, but it is not this node's turn yet.
+    NotReady,
+    /// The engine requires external input (e.g. PoW) to seal a block.
+    External,
+}
+
 /// A consensus mechanism for the chain.
 pub trait ConsensusEngine<M: Machine>: Sync + Send {
     /// The name of this engine.
@@ -47,10 +60,16 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
     /// The number of additional header fields required for this engine.
     fn seal_fields(&self, _header: &M::Header) -> usize { 0 }
 
-    /// None means that it requires external input (e.g. PoW) to seal a block.
-    /// Some(true) means the engine is currently prime for seal generation (i.e. node is the current validator).
-    /// Some(false) means that the node might seal internally but is not qualified now.
-    fn seals_internally(&self) -> Option<bool> { None }
+    /// Whether this engine can generate seals without external input, and if so
+    /// whether it is this node's turn to do so right now.
+    fn sealing_state(&self) -> SealingState { SealingState::External }
+
+    /// Whether a newly imported transaction or a change of the chain head should
+    /// trigger the authoring code to reseal the pending block.
+    ///
+    /// Internal-sealing engines that only produce a seal on their own schedule
+    /// (e.g. waiting for a fixed step to elapse) should return `false` here.
+    fn should_reseal_on_update(&self) -> bool { false }
 
     /// Attempt to seal the block internally.
     ///
@@ -107,6 +126,14 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
     /// has reached finality. The `Headers` given are not guaranteed to return any blocks
     /// from any epoch other than the current.
     ///
+    /// Engines that require finality before enacting a signalled transition should track
+    /// it with a `finality::RollingFinality` and only return a proof once the signalling
+    /// block itself has been finalized. Such engines should wrap the proof with
+    /// `finality::encode_signalled_proof`, encoding the signalling block's number
+    /// alongside it, so that `epoch_verifier` can recheck that block's finality
+    /// (via `finality::decode_signalled_proof`) during snapshot restoration instead
+    /// of trusting the embedded proof blindly.
+    ///
     /// Return optional transition proof.
     fn is_epoch_end(
         &self,
@@ -127,10 +154,16 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
     fn step(&self) {}
 
     /// Block transformation functions, before the transactions.
+    ///
+    /// `ancestry` lazily yields the block's ancestors back to the start of the
+    /// current epoch, newest first. It is supplied by the client; engines that
+    /// don't need multi-block context (e.g. to rebuild rolling finality state or
+    /// to distribute rewards across recent signers) can simply ignore it.
     fn on_new_block(
         &self,
         _block: &mut M::LiveBlock,
         _epoch_begin: bool,
+        _ancestry: &mut Iterator<Item = M::ExtendedHeader>,
     ) -> Result<(), M::Error> {
         Ok(())
     }
@@ -140,8 +173,50 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
         Ok(())
     }
 
+    /// Generate the engine's own system transactions (e.g. validator-set contract
+    /// calls) to be applied before any user transactions. Signed with `sign`.
+    fn generate_engine_transactions(&self, _block: &M::LiveBlock) -> Result<Vec<SignedTransaction>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Finalize seal-dependent state once the block's seal has been computed,
+    /// e.g. recording the seal into a validator-set contract call.
+    fn on_seal_block(&self, _block: &mut M::LiveBlock) -> Result<(), Error> {
+        Ok(())
+    }
+
     /// Sign using the EngineSigner, to be used for consensus tx signing.
     fn sign(&self, _hash: H256) -> Result<Signature, Error> { unimplemented!() }
+
+    /// Decode this engine's seal fields on `header` into human-readable form,
+    /// e.g. proposer address, round/step numbers, aggregated signatures or
+    /// epoch number, keyed by name. Callers (RPC, explorers, tests) use this
+    /// instead of re-implementing RLP decoding of the opaque seal bytes.
+    fn extra_info(&self, _header: &M::Header) -> BTreeMap<String, String> { BTreeMap::new() }
+
+    /// Decide whether a new block should become the best block in place of the
+    /// current one.
+    ///
+    /// The default compares accumulated total difficulty, as is appropriate for
+    /// PoW chains. Finality-based engines should override this to prefer the
+    /// finalized branch regardless of score, since total difficulty has no
+    /// meaning once blocks can be finalized out of order with respect to it.
+    fn fork_choice(&self, new: &M::ExtendedHeader, best: &M::ExtendedHeader) -> ForkChoice {
+        if new.total_score() > best.total_score() {
+            ForkChoice::New
+        } else {
+            ForkChoice::Old
+        }
+    }
+}
+
+/// Decision on whether a competing block should replace the current best block.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// Import the new block as the best block.
+    New,
+    /// Keep the current best block.
+    Old,
 }
 
 /// Results of a query of whether an epoch change occurred at the given block.
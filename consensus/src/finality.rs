@@ -0,0 +1,178 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use codechain_types::{Address, H256};
+use rlp::{DecoderError, RlpStream, UntrustedRlp};
+
+use super::engine::EngineError;
+
+/// Encode a transition proof together with the number of the signalling block,
+/// so that a verifier can recheck that block's finality during snapshot
+/// restoration instead of trusting the embedded proof blindly.
+pub fn encode_signalled_proof(signal_number: u64, proof: &[u8]) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&signal_number).append(&proof);
+    stream.out()
+}
+
+/// Inverse of `encode_signalled_proof`.
+pub fn decode_signalled_proof(combined: &[u8]) -> Result<(u64, Vec<u8>), DecoderError> {
+    let rlp = UntrustedRlp::new(combined);
+    Ok((rlp.val_at(0)?, rlp.val_at(1)?))
+}
+
+/// Rolling finality checker for PoA-style engines.
+///
+/// Tracks the unfinalized suffix of the imported chain as a sliding window of
+/// `(hash, signer)` pairs, together with how many blocks in that window each
+/// validator has signed. A block becomes final once a strict majority of the
+/// current validator set has signed it or some block after it, which lets
+/// `is_epoch_end` withhold a transition proof until the signalling block
+/// itself is final.
+///
+/// The genesis block must never be pushed here: it has no signer and is
+/// trivially final, so it is never part of the rolling window.
+pub struct RollingFinality {
+    validators: HashSet<Address>,
+    headers: VecDeque<(H256, Address)>,
+    sign_count: HashMap<Address, usize>,
+}
+
+impl RollingFinality {
+    /// Create a new checker with an empty window over the given validator set.
+    pub fn new<I: IntoIterator<Item = Address>>(validators: I) -> Self {
+        RollingFinality {
+            validators: validators.into_iter().collect(),
+            headers: VecDeque::new(),
+            sign_count: HashMap::new(),
+        }
+    }
+
+    /// Rebuild the rolling window from ancestry, oldest to newest, so that
+    /// finality state can be recovered on node restart. Runs the same
+    /// finalize-and-pop logic as `push_hash`, so the rebuilt window ends up
+    /// identical to one built incrementally as each block was imported.
+    pub fn build_ancestry_subchain<I>(&mut self, ancestry: I) -> Result<(), EngineError>
+    where
+        I: IntoIterator<Item = (H256, Address)>,
+    {
+        self.headers.clear();
+        self.sign_count.clear();
+
+        for (hash, signer) in ancestry {
+            self.push_hash(hash, signer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Push a newly-imported block signed by `signer` onto the window.
+    ///
+    /// Returns the hashes that became finalized as a result, oldest first;
+    /// empty if the window is still unfinalized.
+    pub fn push_hash(&mut self, hash: H256, signer: Address) -> Result<Vec<H256>, EngineError> {
+        self.note_signed(hash, signer)?;
+
+        let mut finalized = Vec::new();
+        while self.is_finalized() {
+            let (hash, signer) = self.headers.pop_front()
+                .expect("headers non-empty whenever is_finalized() is true; qed");
+
+            let count = self.sign_count.get_mut(&signer)
+                .expect("sign_count incremented for every pushed header; qed");
+            *count -= 1;
+            if *count == 0 {
+                self.sign_count.remove(&signer);
+            }
+
+            finalized.push(hash);
+        }
+
+        Ok(finalized)
+    }
+
+    /// The oldest hash still in the unfinalized window, if any.
+    pub fn subchain_head(&self) -> Option<H256> {
+        self.headers.front().map(|&(hash, _)| hash)
+    }
+
+    fn note_signed(&mut self, hash: H256, signer: Address) -> Result<(), EngineError> {
+        if !self.validators.contains(&signer) {
+            return Err(EngineError::NotAuthorized(signer))
+        }
+
+        self.headers.push_back((hash, signer));
+        *self.sign_count.entry(signer).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Whether the window is currently signed by a strict majority of validators.
+    fn is_finalized(&self) -> bool {
+        self.sign_count.len() > self.validators.len() / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(n: u8) -> Address {
+        let mut bytes = [0u8; 20];
+        bytes[19] = n;
+        Address::from(bytes)
+    }
+
+    #[test]
+    fn finalizes_once_majority_signs() {
+        let mut finality = RollingFinality::new(vec![addr(1), addr(2), addr(3)]);
+
+        assert_eq!(finality.push_hash(H256::from(1), addr(1)).unwrap(), vec![]);
+        // After pushing h2, the window is [(h1,1),(h2,2)] with 2 distinct
+        // signers, a majority of 3: h1 finalizes and is popped, dropping the
+        // window to {addr2} alone, which is no longer a majority — h2 is not
+        // yet final.
+        assert_eq!(finality.push_hash(H256::from(2), addr(2)).unwrap(), vec![H256::from(1)]);
+    }
+
+    #[test]
+    fn rejects_signer_outside_validator_set() {
+        let mut finality = RollingFinality::new(vec![addr(1), addr(2), addr(3)]);
+
+        match finality.push_hash(H256::from(1), addr(9)) {
+            Err(EngineError::NotAuthorized(signer)) => assert_eq!(signer, addr(9)),
+            _ => panic!("expected NotAuthorized"),
+        }
+    }
+
+    #[test]
+    fn build_ancestry_subchain_matches_incremental_push() {
+        let mut incremental = RollingFinality::new(vec![addr(1), addr(2), addr(3)]);
+        incremental.push_hash(H256::from(1), addr(1)).unwrap();
+        incremental.push_hash(H256::from(2), addr(2)).unwrap();
+        incremental.push_hash(H256::from(3), addr(3)).unwrap();
+
+        let mut rebuilt = RollingFinality::new(vec![addr(1), addr(2), addr(3)]);
+        rebuilt.build_ancestry_subchain(vec![
+            (H256::from(1), addr(1)),
+            (H256::from(2), addr(2)),
+            (H256::from(3), addr(3)),
+        ]).unwrap();
+
+        assert_eq!(incremental.subchain_head(), rebuilt.subchain_head());
+    }
+}